@@ -7,7 +7,7 @@ use jetstream_oxide::{
     exports::Nsid,
     DefaultJetstreamEndpoints, JetstreamCompression, JetstreamConfig, JetstreamConnector,
 };
-use meilisearch_sdk::client::*;
+use meilisearch_sdk::{client::*, indexes::Index};
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser)]
@@ -20,11 +20,15 @@ struct Args {
     meili_index: String,
     #[arg(long, default_value = "300")]
     payload_size: NonZeroUsize,
+    /// Number of concurrent sender tasks draining the ingest channel.
+    #[arg(long, default_value = "2")]
+    flush_concurrency: NonZeroUsize,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
-    let Args { meili_url, meili_api_key, meili_index, payload_size } = Args::parse();
+    let Args { meili_url, meili_api_key, meili_index, payload_size, flush_concurrency } =
+        Args::parse();
 
     let like_collection: Nsid = "app.bsky.feed.like".parse().unwrap();
     let config = JetstreamConfig {
@@ -42,7 +46,19 @@ async fn main() -> anyhow::Result<()> {
 
     eprintln!("Listening for '{:?}' events", like_collection);
 
-    let mut outdated = Vec::new();
+    // The receive task parses like edges into `BskyPostLikesOnly` payloads and
+    // pushes them onto a bounded channel; the flush tasks drain it and issue the
+    // batched Meilisearch writes, so a slow index backpressures the firehose.
+    let (sender, ops) = flume::bounded::<BskyPostLikesOnly>(payload_size.get() * flush_concurrency.get());
+
+    let mut flushers = Vec::with_capacity(flush_concurrency.get());
+    for _ in 0..flush_concurrency.get() {
+        let ops = ops.clone();
+        let bsky_posts = bsky_posts.clone();
+        flushers.push(tokio::spawn(flush_task(ops, bsky_posts, payload_size)));
+    }
+    drop(ops);
+
     while let Ok(event) = receiver.recv_async().await {
         if let Commit(commit) = event {
             match commit {
@@ -52,18 +68,15 @@ async fn main() -> anyhow::Result<()> {
                         // at://did:plc:wa7b35aakoll7hugkrjtf3xf/app.bsky.feed.post/3l3pte3p2e325
                         let (_, post_rkey) = record.data.subject.uri.rsplit_once('/').unwrap();
 
-                        outdated.push(BskyPostLikesOnly {
-                            rkey: post_rkey.to_owned(),
-                            context: Context {
-                                add_likes: vec![commit.info.rkey],
-                                remove_likes: vec![],
-                            },
-                        });
-
-                        if outdated.len() == payload_size.get() {
-                            bsky_posts.add_or_update(&outdated, Some("rkey")).await?;
-                            outdated.clear();
-                        }
+                        sender
+                            .send_async(BskyPostLikesOnly {
+                                rkey: post_rkey.to_owned(),
+                                context: Context {
+                                    add_likes: vec![commit.info.rkey],
+                                    remove_likes: vec![],
+                                },
+                            })
+                            .await?;
                     }
                 }
                 CommitEvent::Delete { info: _, commit: _ } => {
@@ -85,6 +98,37 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    drop(sender);
+    for flusher in flushers {
+        flusher.await??;
+    }
+
+    Ok(())
+}
+
+/// Drains `ops`, batching like updates up to `payload_size` before issuing the
+/// Meilisearch writes.
+async fn flush_task(
+    ops: flume::Receiver<BskyPostLikesOnly>,
+    bsky_posts: Index,
+    payload_size: NonZeroUsize,
+) -> anyhow::Result<()> {
+    let mut outdated = Vec::new();
+    while let Ok(update) = ops.recv_async().await {
+        outdated.push(update);
+
+        if outdated.len() == payload_size.get() {
+            bsky_posts.add_or_update(&outdated, Some("rkey")).await?;
+            outdated.clear();
+        }
+    }
+
+    // Flush whatever is still buffered once the sender is gone so a clean
+    // shutdown doesn't drop the tail.
+    if !outdated.is_empty() {
+        bsky_posts.add_or_update(&outdated, Some("rkey")).await?;
+    }
+
     Ok(())
 }
 
@@ -1,8 +1,16 @@
-use std::{collections::HashSet, mem::take, num::NonZeroUsize};
+use std::{
+    collections::HashSet,
+    mem::take,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use atrium_api::{
     app::bsky::feed::post,
-    record::KnownRecord::{AppBskyFeedLike, AppBskyFeedPost},
+    record::KnownRecord::{AppBskyFeedLike, AppBskyFeedPost, AppBskyFeedRepost},
 };
 use clap::Parser;
 use itertools::Itertools;
@@ -15,7 +23,8 @@ use jetstream_oxide::{
     exports::Nsid,
     DefaultJetstreamEndpoints, JetstreamCompression, JetstreamConfig, JetstreamConnector,
 };
-use meilisearch_sdk::client::*;
+use chrono::{DateTime, Utc};
+use meilisearch_sdk::{client::*, indexes::Index};
 use redis::AsyncCommands as _;
 use serde::{Deserialize, Serialize};
 use url::Url;
@@ -34,23 +43,35 @@ struct Args {
     send_likes: NonZeroUsize,
     #[arg(long)]
     disable_likes: bool,
+    /// Number of concurrent sender tasks draining the ingest channel.
+    #[arg(long, default_value = "2")]
+    flush_concurrency: NonZeroUsize,
+    /// Redis key under which the last flushed Jetstream cursor is persisted.
+    #[arg(long, default_value = "jetstream:cursor")]
+    cursor_key: String,
+    /// Start from the live firehose instead of resuming from the stored cursor.
+    #[arg(long)]
+    no_resume: bool,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
-    let Args { meili_url, meili_api_key, meili_index, payload_size, send_likes, disable_likes } =
-        Args::parse();
+    let Args {
+        meili_url,
+        meili_api_key,
+        meili_index,
+        payload_size,
+        send_likes,
+        disable_likes,
+        flush_concurrency,
+        cursor_key,
+        no_resume,
+    } = Args::parse();
     let send_likes = (!disable_likes).then_some(send_likes);
 
     let post_collection: Nsid = "app.bsky.feed.post".parse().unwrap();
     let like_collection: Nsid = "app.bsky.feed.like".parse().unwrap();
-    let config = JetstreamConfig {
-        endpoint: DefaultJetstreamEndpoints::USEastOne.into(),
-        wanted_collections: vec![post_collection.clone(), like_collection.clone()],
-        wanted_dids: Vec::new(),
-        compression: JetstreamCompression::Zstd,
-        cursor: None,
-    };
+    let repost_collection: Nsid = "app.bsky.feed.repost".parse().unwrap();
 
     let redis = redis::Client::open("redis://127.0.0.1/")?;
     let mut redis = redis.get_multiplexed_async_connection().await?;
@@ -62,81 +83,338 @@ async fn main() -> anyhow::Result<()> {
         "Server didn't anwsered PONG. Is there a redis/valkey server running?"
     );
 
+    // Resume from just before the last flushed event so a restart doesn't skip
+    // everything that happened while we were down. Jetstream replays events
+    // at/after the cursor; the idempotent `rkey` primary key and the applied-ref
+    // guard below keep that replayed window from inflating anything.
+    let cursor_time_us: Option<u64> =
+        if no_resume { None } else { redis.get(&cursor_key).await? };
+    let cursor = match cursor_time_us {
+        Some(time_us) => {
+            eprintln!("Resuming from cursor {time_us}");
+            DateTime::<Utc>::from_timestamp_micros(time_us as i64)
+        }
+        None => None,
+    };
+
+    let config = JetstreamConfig {
+        endpoint: DefaultJetstreamEndpoints::USEastOne.into(),
+        wanted_collections: vec![
+            post_collection.clone(),
+            like_collection.clone(),
+            repost_collection.clone(),
+        ],
+        wanted_dids: Vec::new(),
+        compression: JetstreamCompression::Zstd,
+        cursor,
+    };
+
     let jetstream = JetstreamConnector::new(config)?;
     let receiver = jetstream.connect().await?;
     let bsky_posts = meili_client.index(meili_index);
 
-    eprintln!("Listening for '{:?}' and '{:?}' events", post_collection, like_collection);
+    eprintln!(
+        "Listening for '{:?}', '{:?}' and '{:?}' events",
+        post_collection, like_collection, repost_collection
+    );
+
+    // Split ingestion from flushing: the receive task only parses events into
+    // `Op`s and pushes them onto a bounded channel, while the flush tasks drain
+    // the channel and issue the Meilisearch/Redis round-trips. The bound means a
+    // slow Meilisearch naturally backpressures the firehose instead of growing
+    // memory without limit.
+    // Each flush task owns its own channel and a post's rkey is always routed to
+    // the same task. Upserts and deletions for one rkey are not commutative, so
+    // draining them through a single task preserves the firehose's last-writer
+    // order; a shared channel would let a later `Delete` overtake an earlier
+    // `Post` flushed by a sibling and resurrect the post. Like/repost counter
+    // edges are commutative, but routing them by the post they target keeps all
+    // work for a post on one task too.
+    //
+    // Per-task high-water marks. The persisted cursor is the minimum across all
+    // tasks: because the channel is FIFO and each task flushes its batches in
+    // order, every event at/below that minimum has definitely been flushed, so a
+    // task that raced ahead cannot advance the cursor past a sibling's still
+    // buffered events. Slots are seeded with the startup cursor so that a task
+    // which hasn't flushed yet holds the resume point rather than 0, which would
+    // otherwise drag the minimum back to the epoch and replay the whole buffer.
+    let seed = cursor_time_us.unwrap_or(0);
+    let watermarks: Arc<Vec<AtomicU64>> =
+        Arc::new((0..flush_concurrency.get()).map(|_| AtomicU64::new(seed)).collect());
+
+    let mut senders = Vec::with_capacity(flush_concurrency.get());
+    let mut flushers = Vec::with_capacity(flush_concurrency.get());
+    for idx in 0..flush_concurrency.get() {
+        let (sender, ops) = flume::bounded::<(u64, Op)>(payload_size.get());
+        let bsky_posts = bsky_posts.clone();
+        let redis = redis.clone();
+        let cursor_key = cursor_key.clone();
+        let watermarks = watermarks.clone();
+        senders.push(sender);
+        flushers.push(tokio::spawn(flush_task(
+            ops,
+            bsky_posts,
+            redis,
+            payload_size,
+            send_likes,
+            cursor_key,
+            idx,
+            watermarks,
+        )));
+    }
 
-    let mut cache = Vec::new();
-    let mut caches_sent: usize = 0;
-    let mut outdateds = HashSet::new();
     while let Ok(event) = receiver.recv_async().await {
         if let Commit(commit) = event {
             match commit {
                 CommitEvent::Create { info, commit } | CommitEvent::Update { info, commit } => {
+                    let time_us = info.time_us;
                     if let AppBskyFeedPost(record) = commit.record {
-                        cache.push(BskyPost::new(info, commit.info, record.data));
-
-                        if cache.len() == payload_size.get() {
-                            bsky_posts.add_or_update(&cache, Some("rkey")).await?;
-                            caches_sent += 1;
-                            cache.clear();
-                        }
-
-                        if send_likes.map_or(false, |sl| caches_sent % sl.get() == 0) {
-                            let size = 100;
-                            for rkeys in take(&mut outdateds).into_iter().chunks(size).into_iter() {
-                                let rkeys: Vec<_> = rkeys.collect();
-                                let values: Vec<usize> = redis.mget(rkeys.clone()).await?;
-                                let updated: Vec<_> = rkeys
-                                    .into_iter()
-                                    .zip(values)
-                                    .map(|(rkey, likes)| BskyPostLikesOnly { rkey, likes })
-                                    .collect();
-                                bsky_posts.add_or_update(&updated, None).await?;
-                                eprintln!("Sent {size} likes updates");
+                        let post = BskyPost::new(info, commit.info, record.data);
+                        let sender = &senders[flusher_for(&post.rkey, senders.len())];
+                        sender.send_async((time_us, Op::Post(Box::new(post)))).await?;
+                    } else {
+                        let ref_rkey = commit.info.rkey.to_string();
+                        if let Some((ref_type, subject_uri)) = match commit.record {
+                            AppBskyFeedLike(record) => {
+                                Some((RefType::Like, record.data.subject.uri))
                             }
-                        }
-                    } else if let AppBskyFeedLike(record) = commit.record {
-                        if send_likes.is_some() {
-                            // at://did:plc:wa7b35aakoll7hugkrjtf3xf/app.bsky.feed.post/3l3pte3p2e325
-                            let (_, post_rkey) = record.data.subject.uri.rsplit_once('/').unwrap();
-
-                            if let Some(BskyPostLikesOnly { rkey: _, likes }) = bsky_posts
-                                .get_document(post_rkey)
-                                .await
-                                .map(Some)
-                                .or_else(convert_invalid_request_to_none)?
-                            {
-                                let () = redis.set_nx(post_rkey, likes).await?;
-                                let _count: isize = redis.incr(post_rkey, 1).await?;
+                            AppBskyFeedRepost(record) => {
+                                Some((RefType::Repost, record.data.subject.uri))
                             }
+                            _ => None,
+                        } {
+                            // at://did:plc:wa7b35aakoll7hugkrjtf3xf/app.bsky.feed.post/3l3pte3p2e325
+                            let (_, post_rkey) = subject_uri.rsplit_once('/').unwrap();
+                            let sender = &senders[flusher_for(post_rkey, senders.len())];
+                            let op = Op::Ref {
+                                ref_type,
+                                post_rkey: post_rkey.to_owned(),
+                                ref_rkey,
+                            };
+                            sender.send_async((time_us, op)).await?;
                         }
                     }
                 }
-                CommitEvent::Delete { info: _, commit } => {
+                CommitEvent::Delete { info, commit } => {
                     if commit.collection == post_collection {
                         let rkey = commit.rkey.to_string();
-                        bsky_posts.delete_document(rkey).await?;
-                    } /* else if commit.collection == like_collection {
-                          at://did:plc:wa7b35aakoll7hugkrjtf3xf/app.bsky.feed.post/3l3pte3p2e325
-                          let (_, post_rkey) = record.data.subject.uri.rsplit_once('/').unwrap();
-                          if bsky_posts
-                              .get_document::<EmptyBskyPost>(post_rkey)
-                              .await
-                              .map(Some)
-                              .or_else(convert_invalid_request_to_none)?
-                              .is_some()
-                          {
-                              likes_accumulator.decrease(post_rkey.to_string());
-                          }
-                      } */
+                        let sender = &senders[flusher_for(&rkey, senders.len())];
+                        sender.send_async((info.time_us, Op::Delete(rkey))).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Closing the senders lets the flush tasks drain and exit cleanly.
+    drop(senders);
+    for flusher in flushers {
+        flusher.await??;
+    }
+
+    Ok(())
+}
+
+/// Picks the flush task that owns `rkey` so every op for a post lands on the
+/// same task and keeps its non-commutative upsert/delete order.
+fn flusher_for(rkey: &str, flushers: usize) -> usize {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    rkey.hash(&mut hasher);
+    (hasher.finish() % flushers as u64) as usize
+}
+
+/// A unit of work handed from the receive task to a flush task.
+#[derive(Debug)]
+enum Op {
+    /// A post to upsert into the index.
+    Post(Box<BskyPost>),
+    /// A like/repost edge whose counter must be incremented. `ref_rkey` is the
+    /// rkey of the like/repost record itself and is used to skip replayed edges.
+    Ref { ref_type: RefType, post_rkey: String, ref_rkey: String },
+    /// A post deletion, by rkey.
+    Delete(String),
+}
+
+/// How long an applied-ref marker survives, in seconds. This only needs to
+/// outlive the replay window a single restart produces, so it is kept short.
+const APPLIED_REF_TTL: i64 = 60 * 60;
+
+/// Drains `ops`, batching posts up to `payload_size` and routing engagement
+/// edges to their Redis counters, then periodically flushing the accumulated
+/// counters back into the documents. The `time_us` paired with each op is
+/// advances this task's slot in `watermarks`; `cursor_key` is then set to the
+/// minimum across all slots so a restart resumes from the oldest event any
+/// task has yet to flush.
+async fn flush_task(
+    ops: flume::Receiver<(u64, Op)>,
+    bsky_posts: Index,
+    mut redis: redis::aio::MultiplexedConnection,
+    payload_size: NonZeroUsize,
+    send_likes: Option<NonZeroUsize>,
+    cursor_key: String,
+    idx: usize,
+    watermarks: Arc<Vec<AtomicU64>>,
+) -> anyhow::Result<()> {
+    let mut cache = Vec::new();
+    let mut caches_sent: usize = 0;
+    let mut outdateds = HashSet::new();
+    let mut last_time_us = 0;
+
+    while let Ok((time_us, op)) = ops.recv_async().await {
+        last_time_us = last_time_us.max(time_us);
+        match op {
+            Op::Post(post) => {
+                // A reply edge increments the reply counter of the post it answers,
+                // mirroring how likes/reposts feed their own counters. The post's
+                // own rkey guards the increment against replayed posts.
+                if send_likes.is_some() {
+                    if let Some(parent_rkey) = post.reply_parent_rkey() {
+                        let parent_rkey = parent_rkey.to_owned();
+                        count_ref(
+                            &bsky_posts,
+                            &mut redis,
+                            RefType::Reply,
+                            &parent_rkey,
+                            &post.rkey,
+                            &mut outdateds,
+                        )
+                        .await?;
+                    }
+                }
+
+                cache.push(*post);
+
+                if cache.len() == payload_size.get() {
+                    bsky_posts.add_or_update(&cache, Some("rkey")).await?;
+                    caches_sent += 1;
+                    cache.clear();
+                    commit_cursor(&mut redis, &cursor_key, idx, &watermarks, last_time_us).await?;
+                }
+
+                if send_likes.map_or(false, |sl| caches_sent % sl.get() == 0) {
+                    flush_engagement(&bsky_posts, &mut redis, &mut outdateds).await?;
+                }
+            }
+            Op::Ref { ref_type, post_rkey, ref_rkey } => {
+                if send_likes.is_some() {
+                    count_ref(
+                        &bsky_posts,
+                        &mut redis,
+                        ref_type,
+                        &post_rkey,
+                        &ref_rkey,
+                        &mut outdateds,
+                    )
+                    .await?;
                 }
             }
+            Op::Delete(rkey) => {
+                // Drop a still-buffered upsert of this post so the later batch
+                // flush cannot re-add it after the deletion.
+                cache.retain(|post: &BskyPost| post.rkey != rkey);
+                bsky_posts.delete_document(rkey).await?;
+            }
         }
     }
 
+    // Flush whatever is still buffered once the sender is gone so a clean
+    // shutdown doesn't drop the tail.
+    if !cache.is_empty() {
+        bsky_posts.add_or_update(&cache, Some("rkey")).await?;
+        commit_cursor(&mut redis, &cursor_key, idx, &watermarks, last_time_us).await?;
+    }
+    if send_likes.is_some() {
+        flush_engagement(&bsky_posts, &mut redis, &mut outdateds).await?;
+    }
+
+    Ok(())
+}
+
+/// Records this task's latest flushed `time_us` in its slot and persists the
+/// minimum across all slots as the resume cursor. Taking the minimum keeps the
+/// stored cursor at or below the oldest event any task still has buffered, so a
+/// task that has raced ahead can't advance the cursor past a sibling's
+/// unflushed events.
+async fn commit_cursor(
+    redis: &mut redis::aio::MultiplexedConnection,
+    cursor_key: &str,
+    idx: usize,
+    watermarks: &[AtomicU64],
+    last_time_us: u64,
+) -> anyhow::Result<()> {
+    watermarks[idx].store(last_time_us, Ordering::SeqCst);
+    let low_water = watermarks.iter().map(|w| w.load(Ordering::SeqCst)).min().unwrap_or(0);
+    let () = redis.set(cursor_key, low_water).await?;
+    Ok(())
+}
+
+/// Seeds the per-type counter from the document the first time we see the post,
+/// then increments it. Namespacing the key by ref type keeps likes, reposts and
+/// replies accumulating independently. `ref_rkey` identifies the edge record so
+/// a replayed event (after a cursor-based resume) is counted at most once.
+async fn count_ref(
+    bsky_posts: &Index,
+    redis: &mut redis::aio::MultiplexedConnection,
+    ref_type: RefType,
+    post_rkey: &str,
+    ref_rkey: &str,
+    outdateds: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    // Claim the edge; if it was already applied we must not count it again.
+    let applied_key = format!("applied:{}:{ref_rkey}", ref_type.redis_prefix());
+    let first_time: bool = redis.set_nx(&applied_key, 1).await?;
+    if !first_time {
+        return Ok(());
+    }
+    let () = redis.expire(&applied_key, APPLIED_REF_TTL).await?;
+
+    if let Some(engagement) = bsky_posts
+        .get_document::<BskyPostEngagement>(post_rkey)
+        .await
+        .map(Some)
+        .or_else(convert_invalid_request_to_none)?
+    {
+        let key = ref_type.redis_key(post_rkey);
+        let () = redis.set_nx(&key, ref_type.count_of(&engagement)).await?;
+        let _count: isize = redis.incr(&key, 1).await?;
+        outdateds.insert(post_rkey.to_owned());
+    }
+    Ok(())
+}
+
+/// Reads the accumulated counters for every outdated post and writes the fresh
+/// engagement fields back into the documents.
+async fn flush_engagement(
+    bsky_posts: &Index,
+    redis: &mut redis::aio::MultiplexedConnection,
+    outdateds: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let size = 100;
+    for rkeys in take(outdateds).into_iter().chunks(size).into_iter() {
+        let rkeys: Vec<_> = rkeys.collect();
+        let like_keys: Vec<_> = rkeys.iter().map(|rkey| RefType::Like.redis_key(rkey)).collect();
+        let repost_keys: Vec<_> =
+            rkeys.iter().map(|rkey| RefType::Repost.redis_key(rkey)).collect();
+        let reply_keys: Vec<_> = rkeys.iter().map(|rkey| RefType::Reply.redis_key(rkey)).collect();
+        let likes: Vec<Option<usize>> = redis.mget(&like_keys).await?;
+        let reposts: Vec<Option<usize>> = redis.mget(&repost_keys).await?;
+        let replies: Vec<Option<usize>> = redis.mget(&reply_keys).await?;
+        let updated: Vec<_> = rkeys
+            .into_iter()
+            .zip(likes)
+            .zip(reposts)
+            .zip(replies)
+            .map(|(((rkey, likes), reposts), replies)| BskyPostEngagement {
+                rkey,
+                likes: likes.unwrap_or_default(),
+                reposts: reposts.unwrap_or_default(),
+                replies: replies.unwrap_or_default(),
+            })
+            .collect();
+        bsky_posts.add_or_update(&updated, None).await?;
+        eprintln!("Sent {size} engagement updates");
+    }
     Ok(())
 }
 
@@ -152,8 +430,22 @@ struct BskyPost {
     created_at_timestamp: u64,
     // https://bsky.app/profile/did:plc:olsofbpplu7b2hd7amjxrei5/post/3ll2v3rx4ss23
     link: Url,
-    #[serde(skip_serializing_if = "Option::is_some")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_root_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_parent_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_root_rkey: Option<String>,
+    external_links: Vec<ExternalLink>,
+    image_alts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quoted_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quoted_rkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     likes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reposts: Option<usize>,
 }
 
 impl BskyPost {
@@ -168,6 +460,22 @@ impl BskyPost {
             rkey = commit_info.rkey,
         );
 
+        let (reply_root_uri, reply_parent_uri) = record_data.reply.as_ref().map_or(
+            (None, None),
+            |reply| {
+                (Some(reply.root.uri.clone()), Some(reply.parent.uri.clone()))
+            },
+        );
+        let reply_root_rkey = reply_root_uri
+            .as_ref()
+            .and_then(|uri| uri.rsplit_once('/').map(|(_, rkey)| rkey.to_owned()));
+
+        let Embeds { external_links, image_alts, quoted_uri } =
+            record_data.embed.map(Embeds::from_embed).unwrap_or_default();
+        let quoted_rkey = quoted_uri
+            .as_ref()
+            .and_then(|uri| uri.rsplit_once('/').map(|(_, rkey)| rkey.to_owned()));
+
         BskyPost {
             rkey: commit_info.rkey.to_string(),
             langs: record_data.langs.map_or_else(Vec::new, |langs| {
@@ -184,17 +492,142 @@ impl BskyPost {
             created_at: record_data.created_at.as_ref().to_string(),
             created_at_timestamp: event_info.time_us,
             link: link.parse().unwrap(),
+            reply_root_uri,
+            reply_parent_uri,
+            reply_root_rkey,
+            external_links,
+            image_alts,
+            quoted_uri,
+            quoted_rkey,
             likes: None,
+            reposts: None,
         }
     }
+
+    /// The rkey of the post this one directly replies to, if any. Extracted from
+    /// `reply_parent_uri` the same way the like/repost subject uris are parsed.
+    fn reply_parent_rkey(&self) -> Option<&str> {
+        self.reply_parent_uri.as_deref().and_then(|uri| uri.rsplit_once('/').map(|(_, rkey)| rkey))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct BskyPostLikesOnly {
+struct ExternalLink {
+    uri: String,
+    title: String,
+}
+
+/// The searchable pieces pulled out of a post's `embed`: external link
+/// URIs/titles, image alt-text, and the URI of a quoted post.
+#[derive(Default)]
+struct Embeds {
+    external_links: Vec<ExternalLink>,
+    image_alts: Vec<String>,
+    quoted_uri: Option<String>,
+}
+
+impl Embeds {
+    fn from_embed(embed: atrium_api::types::Union<post::RecordEmbedRefs>) -> Self {
+        use atrium_api::app::bsky::embed::record_with_media::MainMediaRefs;
+        use atrium_api::types::Union;
+        use post::RecordEmbedRefs::{
+            AppBskyEmbedExternalMain, AppBskyEmbedImagesMain, AppBskyEmbedRecordMain,
+            AppBskyEmbedRecordWithMediaMain,
+        };
+
+        let mut embeds = Embeds::default();
+        let refs = match embed {
+            Union::Refs(refs) => refs,
+            Union::Unknown(_) => return embeds,
+        };
+
+        match refs {
+            AppBskyEmbedImagesMain(images) => embeds.push_images(images.data.images),
+            AppBskyEmbedExternalMain(external) => embeds.push_external(external.data.external),
+            AppBskyEmbedRecordMain(record) => {
+                embeds.quoted_uri = Some(record.data.record.uri.clone());
+            }
+            AppBskyEmbedRecordWithMediaMain(rwm) => {
+                embeds.quoted_uri = Some(rwm.data.record.record.uri.clone());
+                if let Union::Refs(media) = rwm.data.media {
+                    match media {
+                        MainMediaRefs::AppBskyEmbedImagesMain(images) => {
+                            embeds.push_images(images.data.images)
+                        }
+                        MainMediaRefs::AppBskyEmbedExternalMain(external) => {
+                            embeds.push_external(external.data.external)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        embeds
+    }
+
+    fn push_images(
+        &mut self,
+        images: Vec<atrium_api::app::bsky::embed::images::Image>,
+    ) {
+        self.image_alts
+            .extend(images.into_iter().map(|image| image.data.alt).filter(|alt| !alt.is_empty()));
+    }
+
+    fn push_external(&mut self, external: atrium_api::app::bsky::embed::external::External) {
+        let external = external.data;
+        self.external_links.push(ExternalLink { uri: external.uri, title: external.title });
+    }
+}
+
+/// The kind of engagement reference a record expresses about a post. Both
+/// `app.bsky.feed.like` and `app.bsky.feed.repost` point at a post through an
+/// identical `subject.uri`, so they share the same counter machinery and only
+/// differ in which Redis namespace and document field they feed.
+#[derive(Clone, Copy, Debug)]
+enum RefType {
+    Like,
+    Repost,
+    Reply,
+}
+
+impl RefType {
+    /// The Redis key namespace prefix for this reference type.
+    fn redis_prefix(self) -> &'static str {
+        match self {
+            RefType::Like => "likes",
+            RefType::Repost => "reposts",
+            RefType::Reply => "replies",
+        }
+    }
+
+    /// The Redis key holding the running counter for `post_rkey`.
+    fn redis_key(self, post_rkey: &str) -> String {
+        format!("{}:{post_rkey}", self.redis_prefix())
+    }
+
+    /// The counter to seed from an existing document for this reference type.
+    fn count_of(self, engagement: &BskyPostEngagement) -> usize {
+        match self {
+            RefType::Like => engagement.likes,
+            RefType::Repost => engagement.reposts,
+            RefType::Reply => engagement.replies,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct BskyPostEngagement {
     rkey: String,
     #[serde(default)]
     likes: usize,
+    #[serde(default)]
+    reposts: usize,
+    #[serde(default)]
+    replies: usize,
 }
 
 fn convert_invalid_request_to_none<T>(
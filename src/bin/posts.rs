@@ -11,7 +11,7 @@ use jetstream_oxide::{
     exports::Nsid,
     DefaultJetstreamEndpoints, JetstreamCompression, JetstreamConfig, JetstreamConnector,
 };
-use meilisearch_sdk::client::*;
+use meilisearch_sdk::{client::*, indexes::Index};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -25,11 +25,15 @@ struct Args {
     meili_index: String,
     #[arg(long, default_value = "500")]
     payload_size: NonZeroUsize,
+    /// Number of concurrent sender tasks draining the ingest channel.
+    #[arg(long, default_value = "2")]
+    flush_concurrency: NonZeroUsize,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
-    let Args { meili_url, meili_api_key, meili_index, payload_size } = Args::parse();
+    let Args { meili_url, meili_api_key, meili_index, payload_size, flush_concurrency } =
+        Args::parse();
 
     let post_collection: Nsid = "app.bsky.feed.post".parse().unwrap();
     let config = JetstreamConfig {
@@ -47,48 +51,113 @@ async fn main() -> anyhow::Result<()> {
 
     eprintln!("Listening for '{:?}' events", post_collection);
 
-    let mut cache = HashMap::new();
+    // A lightweight receive task parses events into `Op`s and pushes them onto a
+    // bounded channel; the flush tasks drain the channel and issue the batched
+    // Meilisearch writes. The bound backpressures ingestion when Meilisearch is
+    // slow instead of letting the cache grow without limit.
+    //
+    // Each flush task owns its own channel and a post's rkey is always routed to
+    // the same task. Upserts and deletions for one rkey are not commutative, so
+    // draining them through a single task preserves the firehose's last-writer
+    // order; a shared channel would let a later `Delete` overtake an earlier
+    // `Upsert` flushed by a sibling and resurrect the post.
+    let mut senders = Vec::with_capacity(flush_concurrency.get());
+    let mut flushers = Vec::with_capacity(flush_concurrency.get());
+    for _ in 0..flush_concurrency.get() {
+        let (sender, ops) = flume::bounded::<Op>(payload_size.get());
+        let bsky_posts = bsky_posts.clone();
+        senders.push(sender);
+        flushers.push(tokio::spawn(flush_task(ops, bsky_posts, payload_size)));
+    }
+
     while let Ok(event) = receiver.recv_async().await {
         if let Commit(commit) = event {
             match commit {
                 CommitEvent::Create { info, commit } | CommitEvent::Update { info, commit } => {
                     if let AppBskyFeedPost(record) = commit.record {
                         let post = BskyPost::new(info, commit.info, record.data);
-                        cache.insert(post.rkey.clone(), Some(post));
-
-                        if cache.len() == payload_size.get() {
-                            let (posts, deletions) =
-                                partition_additions_and_deletions(cache.drain());
-                            if !posts.is_empty() {
-                                bsky_posts.add_or_update(&posts, Some("rkey")).await?;
-                            }
-                            if !deletions.is_empty() {
-                                bsky_posts.delete_documents(&deletions).await?;
-                            }
-                        }
+                        let sender = &senders[flusher_for(&post.rkey, senders.len())];
+                        sender.send_async(Op::Upsert(Box::new(post))).await?;
                     }
                 }
                 CommitEvent::Delete { info: _, commit } => {
                     if commit.collection == post_collection {
                         let rkey = commit.rkey.to_string();
-                        cache.insert(rkey, None);
-
-                        if cache.len() == payload_size.get() {
-                            let (posts, deletions) =
-                                partition_additions_and_deletions(cache.drain());
-                            if !posts.is_empty() {
-                                bsky_posts.add_or_update(&posts, Some("rkey")).await?;
-                            }
-                            if !deletions.is_empty() {
-                                bsky_posts.delete_documents(&deletions).await?;
-                            }
-                        }
+                        let sender = &senders[flusher_for(&rkey, senders.len())];
+                        sender.send_async(Op::Delete(rkey)).await?;
                     }
                 }
             }
         }
     }
 
+    drop(senders);
+    for flusher in flushers {
+        flusher.await??;
+    }
+
+    Ok(())
+}
+
+/// Picks the flush task that owns `rkey` so every op for a post lands on the
+/// same task and keeps its non-commutative upsert/delete order.
+fn flusher_for(rkey: &str, flushers: usize) -> usize {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    rkey.hash(&mut hasher);
+    (hasher.finish() % flushers as u64) as usize
+}
+
+/// A unit of work handed from the receive task to a flush task.
+#[derive(Debug)]
+enum Op {
+    /// A post to upsert, keyed by rkey.
+    Upsert(Box<BskyPost>),
+    /// A post deletion, by rkey.
+    Delete(String),
+}
+
+/// Drains `ops`, batching upserts and deletions up to `payload_size` before
+/// issuing the Meilisearch writes.
+async fn flush_task(
+    ops: flume::Receiver<Op>,
+    bsky_posts: Index,
+    payload_size: NonZeroUsize,
+) -> anyhow::Result<()> {
+    let mut cache = HashMap::new();
+    while let Ok(op) = ops.recv_async().await {
+        match op {
+            Op::Upsert(post) => {
+                cache.insert(post.rkey.clone(), Some(*post));
+            }
+            Op::Delete(rkey) => {
+                cache.insert(rkey, None);
+            }
+        }
+
+        if cache.len() == payload_size.get() {
+            let (posts, deletions) = partition_additions_and_deletions(cache.drain());
+            if !posts.is_empty() {
+                bsky_posts.add_or_update(&posts, Some("rkey")).await?;
+            }
+            if !deletions.is_empty() {
+                bsky_posts.delete_documents(&deletions).await?;
+            }
+        }
+    }
+
+    // Flush whatever is still buffered once the sender is gone so a clean
+    // shutdown doesn't drop the tail.
+    if !cache.is_empty() {
+        let (posts, deletions) = partition_additions_and_deletions(cache.drain());
+        if !posts.is_empty() {
+            bsky_posts.add_or_update(&posts, Some("rkey")).await?;
+        }
+        if !deletions.is_empty() {
+            bsky_posts.delete_documents(&deletions).await?;
+        }
+    }
+
     Ok(())
 }
 
@@ -120,6 +189,12 @@ struct BskyPost {
     created_at_timestamp: u64,
     // https://bsky.app/profile/did:plc:olsofbpplu7b2hd7amjxrei5/post/3ll2v3rx4ss23
     link: Url,
+    external_links: Vec<ExternalLink>,
+    image_alts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quoted_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quoted_rkey: Option<String>,
     #[serde(skip_serializing_if = "Option::is_some")]
     likes: Option<usize>,
 }
@@ -136,6 +211,12 @@ impl BskyPost {
             rkey = commit_info.rkey,
         );
 
+        let Embeds { external_links, image_alts, quoted_uri } =
+            record_data.embed.map(Embeds::from_embed).unwrap_or_default();
+        let quoted_rkey = quoted_uri
+            .as_ref()
+            .and_then(|uri| uri.rsplit_once('/').map(|(_, rkey)| rkey.to_owned()));
+
         BskyPost {
             rkey: commit_info.rkey.to_string(),
             langs: record_data.langs.map_or_else(Vec::new, |langs| {
@@ -152,7 +233,79 @@ impl BskyPost {
             created_at: record_data.created_at.as_ref().to_string(),
             created_at_timestamp: event_info.time_us,
             link: link.parse().unwrap(),
+            external_links,
+            image_alts,
+            quoted_uri,
+            quoted_rkey,
             likes: None,
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExternalLink {
+    uri: String,
+    title: String,
+}
+
+/// The searchable pieces pulled out of a post's `embed`: external link
+/// URIs/titles, image alt-text, and the URI of a quoted post.
+#[derive(Default)]
+struct Embeds {
+    external_links: Vec<ExternalLink>,
+    image_alts: Vec<String>,
+    quoted_uri: Option<String>,
+}
+
+impl Embeds {
+    fn from_embed(embed: atrium_api::types::Union<post::RecordEmbedRefs>) -> Self {
+        use atrium_api::app::bsky::embed::record_with_media::MainMediaRefs;
+        use atrium_api::types::Union;
+        use post::RecordEmbedRefs::{
+            AppBskyEmbedExternalMain, AppBskyEmbedImagesMain, AppBskyEmbedRecordMain,
+            AppBskyEmbedRecordWithMediaMain,
+        };
+
+        let mut embeds = Embeds::default();
+        let refs = match embed {
+            Union::Refs(refs) => refs,
+            Union::Unknown(_) => return embeds,
+        };
+
+        match refs {
+            AppBskyEmbedImagesMain(images) => embeds.push_images(images.data.images),
+            AppBskyEmbedExternalMain(external) => embeds.push_external(external.data.external),
+            AppBskyEmbedRecordMain(record) => {
+                embeds.quoted_uri = Some(record.data.record.uri.clone());
+            }
+            AppBskyEmbedRecordWithMediaMain(rwm) => {
+                embeds.quoted_uri = Some(rwm.data.record.record.uri.clone());
+                if let Union::Refs(media) = rwm.data.media {
+                    match media {
+                        MainMediaRefs::AppBskyEmbedImagesMain(images) => {
+                            embeds.push_images(images.data.images)
+                        }
+                        MainMediaRefs::AppBskyEmbedExternalMain(external) => {
+                            embeds.push_external(external.data.external)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        embeds
+    }
+
+    fn push_images(&mut self, images: Vec<atrium_api::app::bsky::embed::images::Image>) {
+        self.image_alts
+            .extend(images.into_iter().map(|image| image.data.alt).filter(|alt| !alt.is_empty()));
+    }
+
+    fn push_external(&mut self, external: atrium_api::app::bsky::embed::external::External) {
+        let external = external.data;
+        self.external_links.push(ExternalLink { uri: external.uri, title: external.title });
+    }
+}
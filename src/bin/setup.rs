@@ -1,9 +1,45 @@
-use clap::Parser;
+use std::{fs, path::PathBuf, time::Duration};
+
+use anyhow::Context as _;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
 use serde_json::json;
 use url::Url;
 
-#[derive(Parser)]
-struct Args {
+/// The built-in ranking rules Meilisearch accepts by name (custom
+/// `field:asc`/`field:desc` rules are validated on their `asc`/`desc` suffix).
+const VALID_RANKING_RULES: &[&str] =
+    &["words", "typo", "proximity", "attribute", "sort", "exactness"];
+
+/// Default Liquid document template fed to the embedder. It references the
+/// flattened fields this crate produces so semantic search covers both the post
+/// body and any embedded link preview (external link titles and image alt-text).
+const DEFAULT_DOCUMENT_TEMPLATE: &str = "A Bluesky post: {{doc.text}} \
+    {% for link in doc.externalLinks %}{{link.title}} {% endfor %}\
+    {% for alt in doc.imageAlts %}{{alt}} {% endfor %}";
+
+/// The searchable attributes that benefit from per-language tokenization.
+const LOCALIZED_ATTRIBUTE_PATTERNS: &[&str] = &["text", "externalLinks.title", "imageAlts"];
+
+/// Default locale group, used as a single `localizedAttributes` entry, covering
+/// the non-Latin scripts that need a dedicated segmenter: English, Japanese,
+/// Chinese, Korean and Thai.
+const DEFAULT_LOCALES: &[&str] = &["eng", "jpn", "cmn", "kor", "tha"];
+
+/// The `executeAfterUpdate` script compiled into the binary, applied unless an
+/// external path is passed to `--execute-after-update`.
+const DEFAULT_EXECUTE_AFTER_UPDATE: &str = include_str!("apply_likes.rhai");
+
+#[derive(Clone, Copy, ValueEnum)]
+#[clap(rename_all = "camelCase")]
+enum EmbedderSource {
+    OpenAi,
+    Ollama,
+    Rest,
+}
+
+#[derive(Parser, Clone)]
+struct Connection {
     #[arg(long, default_value = "http://localhost:7700")]
     meili_url: String,
     #[arg(long)]
@@ -12,13 +48,201 @@ struct Args {
     meili_index: String,
 }
 
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    conn: Connection,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the index settings (the default when no subcommand is given).
+    Setup(SetupArgs),
+    /// Run a one-off `editDocumentsByFunction` task against the index.
+    ApplyFunction(ApplyFunctionArgs),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Setup(SetupArgs::default())
+    }
+}
+
+#[derive(Parser, Default)]
+struct SetupArgs {
+    /// Configure an embedder for semantic/hybrid search. When omitted the index
+    /// stays keyword-only (`"embedders": {}`).
+    #[arg(long)]
+    embedder_source: Option<EmbedderSource>,
+    /// Embedder endpoint, for the `ollama`/`rest` sources.
+    #[arg(long)]
+    embedder_url: Option<String>,
+    /// Embedding model name (e.g. `nomic-embed-text`, `text-embedding-3-small`).
+    #[arg(long)]
+    embedder_model: Option<String>,
+    /// API key, for the `openAi` source.
+    #[arg(long)]
+    embedder_api_key: Option<String>,
+    /// Embedding dimensions, for the `openAi` source.
+    #[arg(long)]
+    embedder_dimensions: Option<usize>,
+    /// Liquid template describing the document fed to the embedder.
+    #[arg(long)]
+    embedder_document_template: Option<String>,
+    /// Locale group applying a per-language segmenter/normalizer to the
+    /// searchable text. Repeatable, each occurrence a comma-separated list of
+    /// ISO 639-3 codes, e.g. `--locales eng,fra --locales jpn,cmn`. Defaults to a
+    /// multilingual set covering the non-Latin scripts when omitted.
+    #[arg(long)]
+    locales: Vec<String>,
+    /// Override the facet value ordering for a given facet, e.g.
+    /// `--facet-order tags=count --facet-order mentions=count`. Facets left
+    /// unspecified keep the `"*": "alpha"` fallback.
+    #[arg(long)]
+    facet_order: Vec<String>,
+    /// Path to an external `executeAfterUpdate` script; defaults to the one
+    /// compiled into the binary.
+    #[arg(long)]
+    execute_after_update: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct ApplyFunctionArgs {
+    /// Filter expression selecting the documents to edit.
+    #[arg(long)]
+    filter: String,
+    /// JSON object exposed to the script as `context`.
+    #[arg(long)]
+    context: Option<String>,
+    /// Path to the Rhai function body applied to each matched document.
+    #[arg(long)]
+    script: PathBuf,
+}
+
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
-    let Args { meili_url, meili_api_key, meili_index } = Args::parse();
+    let Args { conn, command } = Args::parse();
+    match command.unwrap_or_default() {
+        Command::Setup(args) => run_setup(&conn, args).await,
+        Command::ApplyFunction(args) => run_apply_function(&conn, args).await,
+    }
+}
+
+async fn run_setup(conn: &Connection, args: SetupArgs) -> anyhow::Result<()> {
+    let SetupArgs {
+        embedder_source,
+        embedder_url,
+        embedder_model,
+        embedder_api_key,
+        embedder_dimensions,
+        embedder_document_template,
+        locales,
+        facet_order,
+        execute_after_update,
+    } = args;
+
+    // Build the `embedders` map from the CLI flags. A missing `--embedder-source`
+    // leaves the index keyword-only.
+    let embedders = match embedder_source {
+        None => json!({}),
+        Some(source) => {
+            let template =
+                embedder_document_template.unwrap_or_else(|| DEFAULT_DOCUMENT_TEMPLATE.to_string());
+            let config = match source {
+                EmbedderSource::OpenAi => json!({
+                    "source": "openAi",
+                    "apiKey": embedder_api_key,
+                    "model": embedder_model,
+                    "dimensions": embedder_dimensions,
+                    "documentTemplate": template,
+                }),
+                EmbedderSource::Ollama => json!({
+                    "source": "ollama",
+                    "url": embedder_url,
+                    "model": embedder_model,
+                    "documentTemplate": template,
+                }),
+                EmbedderSource::Rest => json!({
+                    "source": "rest",
+                    "url": embedder_url,
+                    "model": embedder_model,
+                    "documentTemplate": template,
+                }),
+            };
+            json!({ "bsky": config })
+        }
+    };
+
+    // One `localizedAttributes` entry per `--locales` group, falling back to the
+    // default multilingual set when the flag is omitted.
+    let locale_groups: Vec<Vec<String>> = if locales.is_empty() {
+        vec![DEFAULT_LOCALES.iter().map(|l| l.to_string()).collect()]
+    } else {
+        locales
+            .iter()
+            .map(|group| group.split(',').map(|l| l.trim().to_string()).collect())
+            .collect()
+    };
+    let localized_attributes: Vec<_> = locale_groups
+        .into_iter()
+        .map(|locales| json!({ "locales": locales, "attributePatterns": LOCALIZED_ATTRIBUTE_PATTERNS }))
+        .collect();
+
+    // Per-facet value ordering, keeping alphabetical as the wildcard fallback.
+    let mut sort_facet_values_by = serde_json::Map::new();
+    sort_facet_values_by.insert("*".to_string(), json!("alpha"));
+    for entry in &facet_order {
+        let (facet, order) = entry
+            .split_once('=')
+            .with_context(|| format!("invalid --facet-order '{entry}', expected facet=order"))?;
+        sort_facet_values_by.insert(facet.to_string(), json!(order));
+    }
+
+    let execute_after_update = match execute_after_update {
+        Some(path) => fs::read_to_string(&path)
+            .with_context(|| format!("reading executeAfterUpdate script {}", path.display()))?,
+        None => DEFAULT_EXECUTE_AFTER_UPDATE.to_string(),
+    };
+
+    // Attribute configuration, declared once so it can be both preflight-checked
+    // and serialized. Each filterable group is (patterns, facetSearch, comparison).
+    let sortable_attributes = ["createdAtTimestamp", "likes", "reposts", "replies"];
+    let filterable_groups: &[(&[&str], bool, bool)] = &[
+        (
+            &[
+                "rkey",
+                "likesIds",
+                "quotedUri",
+                "quotedRkey",
+                "externalLinks.uri",
+                "replyRootUri",
+                "replyParentUri",
+                "replyRootRkey",
+            ],
+            false,
+            false,
+        ),
+        (&["mentions", "lang" /* langs in fact */, "tags"], true, false),
+        (&["createdAtTimestamp", "likes", "reposts", "replies"], false, true),
+    ];
+    let ranking_rules = ["words", "typo", "proximity", "attribute", "sort", "exactness"];
 
-    let meili_client = reqwest::Client::new();
-    let mut request = meili_client.patch(format!("{meili_url}/indexes/{meili_index}/settings"));
-    if let Some(api_key) = meili_api_key.as_ref() {
+    // Fail fast on client-side misconfiguration rather than on a server panic.
+    preflight(&sortable_attributes, filterable_groups, &ranking_rules)?;
+
+    let filterable_attributes: Vec<_> = filterable_groups
+        .iter()
+        .map(|(patterns, facet_search, comparison)| {
+            filterable_group(patterns, *facet_search, *comparison)
+        })
+        .collect();
+
+    let client = reqwest::Client::new();
+    let mut request =
+        client.patch(format!("{}/indexes/{}/settings", conn.meili_url, conn.meili_index));
+    if let Some(api_key) = conn.meili_api_key.as_ref() {
         request = request.bearer_auth(api_key);
     };
 
@@ -28,63 +252,12 @@ async fn main() -> anyhow::Result<()> {
       ],
       "searchableAttributes": [
         "text",
-        "embed.title",
-        "embed.description"
-      ],
-      "filterableAttributes": [
-        {
-          "attributePatterns": [
-            "rkey",
-            "likesIds"
-          ],
-          "features": {
-            "facetSearch": false,
-            "filter": {
-              "equality": true,
-              "comparison": false
-            }
-          }
-        },
-        {
-          "attributePatterns": [
-            "mentions",
-            "lang", // langs in fact
-            "tags"
-          ],
-          "features": {
-            "facetSearch": true,
-            "filter": {
-              "equality": true,
-              "comparison": false
-            }
-          }
-        },
-        {
-          "attributePatterns": [
-            "createdAtTimestamp",
-            "likes"
-          ],
-          "features": {
-            "facetSearch": false,
-            "filter": {
-              "equality": true,
-              "comparison": true
-            }
-          }
-        }
-      ],
-      "sortableAttributes": [
-        "createdAtTimestamp",
-        "likes"
-      ],
-      "rankingRules": [
-        "words",
-        "typo",
-        "proximity",
-        "attribute",
-        "sort",
-        "exactness"
+        "externalLinks.title",
+        "imageAlts"
       ],
+      "filterableAttributes": filterable_attributes,
+      "sortableAttributes": sortable_attributes,
+      "rankingRules": ranking_rules,
       "stopWords": [],
       "nonSeparatorTokens": [],
       "separatorTokens": [],
@@ -103,33 +276,167 @@ async fn main() -> anyhow::Result<()> {
       },
       "faceting": {
         "maxValuesPerFacet": 100,
-        "sortFacetValuesBy": {
-          "*": "alpha"
-        }
+        "sortFacetValuesBy": sort_facet_values_by
       },
       "pagination": {
         "maxTotalHits": 1000
       },
-      "embedders": {},
+      "embedders": embedders,
       "searchCutoffMs": null,
-      "localizedAttributes": null,
+      "localizedAttributes": localized_attributes,
       "facetSearch": true,
       "prefixSearch": "indexingTime",
-      "executeAfterUpdate": include_str!("apply_likes.rhai"),
+      "executeAfterUpdate": execute_after_update,
     }));
 
-    let response = request.send().await?;
-    response.error_for_status().unwrap();
+    let task_uid = submit_task(request).await?;
+    wait_for_task(&client, conn, task_uid).await?;
 
-    let raw_client = reqwest::Client::new();
-    let url = Url::parse(&meili_url)?.join("experimental-features")?;
-    let mut request = raw_client.patch(url);
-    request = request.json(&json!({ "editDocumentsByFunction": true }));
-    if let Some(key) = meili_api_key {
+    let url = Url::parse(&conn.meili_url)?.join("experimental-features")?;
+    let mut request = client.patch(url);
+    request = request.json(&json!({ "editDocumentsByFunction": true, "vectorStore": true }));
+    if let Some(key) = conn.meili_api_key.as_ref() {
         request = request.bearer_auth(key);
     }
-    request.send().await?.error_for_status()?;
-    eprintln!("Enabled the editDocumentsByFunction experimental feature");
+    error_for_meili(request.send().await?).await?;
+    eprintln!("Enabled the editDocumentsByFunction and vectorStore experimental features");
+
+    Ok(())
+}
+
+/// Builds a single `filterableAttributes` group.
+fn filterable_group(patterns: &[&str], facet_search: bool, comparison: bool) -> serde_json::Value {
+    json!({
+        "attributePatterns": patterns,
+        "features": {
+            "facetSearch": facet_search,
+            "filter": { "equality": true, "comparison": comparison }
+        }
+    })
+}
+
+/// Client-side checks that fail fast on a misconfiguration Meilisearch would
+/// otherwise reject server-side: unknown ranking rules, or a sortable attribute
+/// that isn't filterable with `comparison: true`.
+fn preflight(
+    sortable: &[&str],
+    filterable: &[(&[&str], bool, bool)],
+    ranking: &[&str],
+) -> anyhow::Result<()> {
+    for rule in ranking {
+        let valid = match rule.split_once(':') {
+            Some((field, order)) => !field.is_empty() && matches!(order, "asc" | "desc"),
+            None => VALID_RANKING_RULES.contains(rule),
+        };
+        anyhow::ensure!(valid, "invalid ranking rule '{rule}'");
+    }
+
+    for attr in sortable {
+        let mut comparable = false;
+        for (patterns, _facet_search, comparison) in filterable {
+            if patterns.contains(attr) {
+                anyhow::ensure!(
+                    *comparison,
+                    "sortable attribute '{attr}' is declared filterable with comparison: false"
+                );
+                comparable = true;
+            }
+        }
+        anyhow::ensure!(
+            comparable,
+            "sortable attribute '{attr}' is not among the filterable attributePatterns"
+        );
+    }
 
     Ok(())
 }
+
+async fn run_apply_function(conn: &Connection, args: ApplyFunctionArgs) -> anyhow::Result<()> {
+    let ApplyFunctionArgs { filter, context, script } = args;
+
+    let function = fs::read_to_string(&script)
+        .with_context(|| format!("reading script {}", script.display()))?;
+    let context: Option<serde_json::Value> = match context {
+        Some(context) => Some(serde_json::from_str(&context).context("parsing --context JSON")?),
+        None => None,
+    };
+
+    let client = reqwest::Client::new();
+    let mut request =
+        client.post(format!("{}/indexes/{}/documents/edit", conn.meili_url, conn.meili_index));
+    if let Some(api_key) = conn.meili_api_key.as_ref() {
+        request = request.bearer_auth(api_key);
+    }
+    request = request.json(&json!({
+        "filter": filter,
+        "context": context,
+        "function": function,
+    }));
+
+    let task_uid = submit_task(request).await?;
+    wait_for_task(&client, conn, task_uid).await
+}
+
+/// Sends an enqueuing request and returns the `taskUid` Meilisearch assigns it.
+async fn submit_task(request: reqwest::RequestBuilder) -> anyhow::Result<u64> {
+    let response = error_for_meili(request.send().await?).await?;
+    let body: serde_json::Value = response.json().await?;
+    body["taskUid"].as_u64().context("Meilisearch response is missing a taskUid")
+}
+
+/// Meilisearch's JSON error body.
+#[derive(Deserialize)]
+struct MeiliError {
+    message: String,
+    code: String,
+    #[serde(rename = "type")]
+    kind: String,
+    link: String,
+}
+
+/// Turns a non-success response into an `anyhow` error carrying Meilisearch's
+/// `code`, `type` and documentation `link` instead of panicking on `.unwrap()`.
+async fn error_for_meili(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await?;
+    match serde_json::from_str::<MeiliError>(&body) {
+        Ok(error) => anyhow::bail!(
+            "Meilisearch returned {status}: {} (code: {}, type: {}, see {})",
+            error.message,
+            error.code,
+            error.kind,
+            error.link,
+        ),
+        Err(_) => anyhow::bail!("Meilisearch returned {status}: {body}"),
+    }
+}
+
+/// Polls `/tasks/{task_uid}` until the task reaches a terminal state, returning
+/// an error carrying Meilisearch's report when it fails.
+async fn wait_for_task(
+    client: &reqwest::Client,
+    conn: &Connection,
+    task_uid: u64,
+) -> anyhow::Result<()> {
+    loop {
+        let mut request = client.get(format!("{}/tasks/{task_uid}", conn.meili_url));
+        if let Some(api_key) = conn.meili_api_key.as_ref() {
+            request = request.bearer_auth(api_key);
+        }
+        let response = error_for_meili(request.send().await?).await?;
+        let task: serde_json::Value = response.json().await?;
+        match task["status"].as_str() {
+            Some("succeeded") => {
+                eprintln!("Task {task_uid} succeeded");
+                return Ok(());
+            }
+            Some("failed") => anyhow::bail!("Task {task_uid} failed: {}", task["error"]),
+            Some("canceled") => anyhow::bail!("Task {task_uid} was canceled"),
+            _ => tokio::time::sleep(Duration::from_millis(500)).await,
+        }
+    }
+}
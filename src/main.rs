@@ -1,12 +1,30 @@
+use std::num::NonZeroUsize;
+
 use atrium_api::record::KnownRecord::AppBskyFeedPost;
+use clap::Parser;
 use jetstream_oxide::{
     events::{commit::CommitEvent, JetstreamEvent::Commit},
     exports::Nsid,
     DefaultJetstreamEndpoints, JetstreamCompression, JetstreamConfig, JetstreamConnector,
 };
-use meilisearch_sdk::client::*;
+use meilisearch_sdk::{client::*, indexes::Index};
 use serde::{Deserialize, Serialize};
 
+#[derive(Parser)]
+struct Args {
+    #[arg(long, default_value = "http://localhost:7700")]
+    meili_url: String,
+    #[arg(long)]
+    meili_api_key: Option<String>,
+    #[arg(long, default_value = "bsky-posts")]
+    meili_index: String,
+    #[arg(long, default_value = "100")]
+    payload_size: NonZeroUsize,
+    /// Number of concurrent sender tasks draining the ingest channel.
+    #[arg(long, default_value = "2")]
+    flush_concurrency: NonZeroUsize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct BskyPost {
@@ -20,6 +38,9 @@ struct BskyPost {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let Args { meili_url, meili_api_key, meili_index, payload_size, flush_concurrency } =
+        Args::parse();
+
     let collection: Nsid = "app.bsky.feed.post".parse().unwrap();
     let config = JetstreamConfig {
         endpoint: DefaultJetstreamEndpoints::USEastOne.into(),
@@ -29,11 +50,11 @@ async fn main() -> anyhow::Result<()> {
         cursor: None,
     };
 
-    let meili_client = Client::new("http://localhost:7700", Option::<String>::None)?;
+    let meili_client = Client::new(&meili_url, meili_api_key.as_ref())?;
     let jetstream = JetstreamConnector::new(config)?;
     let receiver = jetstream.connect().await?;
 
-    let bsky_posts = meili_client.index("bsky-posts");
+    let bsky_posts = meili_client.index(meili_index);
     bsky_posts.set_searchable_attributes(&["text"]).await?;
     bsky_posts
         .set_filterable_attributes(&["createdAtTimestamp", "mentions", "tags", "lang"])
@@ -43,36 +64,40 @@ async fn main() -> anyhow::Result<()> {
 
     eprintln!("Listening for '{:?}' events", collection);
 
-    let mut posts_sent = 0;
+    // The receive task only parses events into `BskyPost`s and pushes them onto
+    // a bounded channel; the flush tasks drain it and issue the batched
+    // Meilisearch writes, so a slow index backpressures the firehose.
+    let (sender, ops) = flume::bounded::<BskyPost>(payload_size.get() * flush_concurrency.get());
+
+    let mut flushers = Vec::with_capacity(flush_concurrency.get());
+    for _ in 0..flush_concurrency.get() {
+        let ops = ops.clone();
+        let bsky_posts = bsky_posts.clone();
+        flushers.push(tokio::spawn(flush_task(ops, bsky_posts, payload_size)));
+    }
+    drop(ops);
+
     while let Ok(event) = receiver.recv_async().await {
         if let Commit(commit) = event {
             match commit {
                 CommitEvent::Create { info: _, commit } => {
                     if let AppBskyFeedPost(record) = commit.record {
                         let record = record.data;
-                        bsky_posts
-                            .add_documents(
-                                &[BskyPost {
-                                    cid: commit.cid.as_ref().to_string(),
-                                    langs: record.langs.map_or_else(Vec::new, |langs| {
-                                        langs
-                                            .into_iter()
-                                            .map(|lang| lang.as_ref().as_str().to_string())
-                                            .collect()
-                                    }),
-                                    text: record.text,
-                                    mentions: Vec::new(),
-                                    tags: Vec::new(),
-                                    created_at_timestamp: record.created_at.as_ref().timestamp(),
-                                }],
-                                Some("cid"),
-                            )
+                        sender
+                            .send_async(BskyPost {
+                                cid: commit.cid.as_ref().to_string(),
+                                langs: record.langs.map_or_else(Vec::new, |langs| {
+                                    langs
+                                        .into_iter()
+                                        .map(|lang| lang.as_ref().as_str().to_string())
+                                        .collect()
+                                }),
+                                text: record.text,
+                                mentions: Vec::new(),
+                                tags: Vec::new(),
+                                created_at_timestamp: record.created_at.as_ref().timestamp(),
+                            })
                             .await?;
-
-                        posts_sent += 1;
-                        if posts_sent % 1000 == 0 {
-                            eprintln!("{posts_sent} posts sent");
-                        }
                     }
                 }
                 // CommitEvent::Delete { info: _, commit } => {
@@ -86,5 +111,41 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    drop(sender);
+    for flusher in flushers {
+        flusher.await??;
+    }
+
+    Ok(())
+}
+
+/// Drains `ops`, batching posts up to `payload_size` before issuing the
+/// Meilisearch writes.
+async fn flush_task(
+    ops: flume::Receiver<BskyPost>,
+    bsky_posts: Index,
+    payload_size: NonZeroUsize,
+) -> anyhow::Result<()> {
+    let mut cache = Vec::new();
+    let mut posts_sent = 0;
+    while let Ok(post) = ops.recv_async().await {
+        cache.push(post);
+
+        if cache.len() == payload_size.get() {
+            bsky_posts.add_documents(&cache, Some("cid")).await?;
+            posts_sent += cache.len();
+            cache.clear();
+            if posts_sent % 1000 == 0 {
+                eprintln!("{posts_sent} posts sent");
+            }
+        }
+    }
+
+    // Flush whatever is still buffered once the sender is gone so a clean
+    // shutdown doesn't drop the tail.
+    if !cache.is_empty() {
+        bsky_posts.add_documents(&cache, Some("cid")).await?;
+    }
+
     Ok(())
 }